@@ -1,17 +1,101 @@
 //! An http routing tool based on routes stored in yaml files.
 
+use arc_swap::ArcSwap;
 use log::{debug, warn};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
 use serde::{Deserialize, Serialize};
 use serde_yaml::{Mapping, Value};
-use std::{collections::HashMap, vec::Vec};
+use std::{collections::HashMap, fmt, sync::Arc, vec::Vec};
 mod routes;
 
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
 #[derive(Debug, Clone)]
 struct Parameter {
     value: String,
     requirement: Option<regex::Regex>,
 }
 
+/// The settings inherited by a group's child routes (and nested groups) when they don't
+/// declare their own, with `prefix` already composed with every enclosing group's prefix.
+pub(crate) struct Group {
+    pub(crate) prefix: String,
+    pub(crate) middleware: Option<String>,
+    pub(crate) methods: Option<String>,
+    pub(crate) language: Option<String>,
+}
+/// Joins a group prefix and a child path, normalizing so a trailing slash on `prefix` and a
+/// leading slash on `path` don't double up.
+pub(crate) fn join_path(prefix: &str, path: &str) -> String {
+    format!("{}/{}", prefix.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The error returned by [`Router::get_uri`] when a uri cannot be generated for a route.
+pub enum UrlGenerationError {
+    /// No route is registered under the given name.
+    RouteNotFound(String),
+    /// A placeholder in the route's path has no matching value among the supplied parameters.
+    MissingParameter { route: String, name: String },
+    /// The supplied value for a parameter doesn't satisfy the route's requirement regex.
+    RequirementMismatch {
+        route: String,
+        name: String,
+        value: String,
+        pattern: String,
+    },
+}
+impl fmt::Display for UrlGenerationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlGenerationError::RouteNotFound(route) => {
+                write!(f, "Route \"{}\" not found.", route)
+            }
+            UrlGenerationError::MissingParameter { route, name } => write!(
+                f,
+                "Parameter \"{}\" is missing for route \"{}\".",
+                name, route
+            ),
+            UrlGenerationError::RequirementMismatch {
+                route,
+                name,
+                value,
+                pattern,
+            } => write!(
+                f,
+                "Value \"{}\" for parameter \"{}\" of route \"{}\" doesn't match the requirement \"{}\".",
+                value, name, route, pattern
+            ),
+        }
+    }
+}
+impl std::error::Error for UrlGenerationError {}
+
+#[derive(Debug, Clone)]
+/// The outcome of matching a uri and method against the routes collection.
+pub enum RouteMatch {
+    /// A route matched both the uri and the method.
+    Found(Route),
+    /// A route matched the uri but not the method; `allowed` lists the methods that would
+    /// have matched, so the caller can build a `405 Method Not Allowed` response.
+    MethodNotAllowed { allowed: Vec<String> },
+    /// No route matched the uri.
+    NotFound,
+}
+impl RouteMatch {
+    /// Returns the matched `Route`, or `None` if the match was `MethodNotAllowed` or `NotFound`.
+    pub fn found(self) -> Option<Route> {
+        match self {
+            RouteMatch::Found(route) => Some(route),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 /// The object that keeps the routes collection.
 ///
@@ -33,7 +117,13 @@ struct Parameter {
 ///         id: "^[0-9]+"
 /// ```
 pub struct Router {
-    pub collection: Vec<Route>,
+    state: Arc<ArcSwap<RouteCollection>>,
+    synthesize_head_and_options: bool,
+}
+#[derive(Debug)]
+struct RouteCollection {
+    collection: Vec<Route>,
+    regex_set: regex::RegexSet,
 }
 #[derive(Serialize, Deserialize, Debug, Clone)]
 /// The object that stores the route information.
@@ -53,24 +143,36 @@ pub struct Route {
     path: String,
     #[serde(skip_serializing, skip_deserializing)]
     path_split: Vec<Parameter>,
+    #[serde(skip_serializing, skip_deserializing, default = "default_regex")]
+    regex: regex::Regex,
     methods: Vec<String>,
     controller: String,
     middleware: String,
     pub parameters: HashMap<String, String>,
     pub language: String,
+    #[serde(skip_serializing, skip_deserializing)]
+    /// `Some("head")` or `Some("options")` when this match was synthesized by the `Router`'s
+    /// opt-in HEAD/OPTIONS support rather than declared explicitly by the route.
+    pub synthesized_method: Option<String>,
 }
 impl Route {
-    /// Creates and returns the `Route` instance given the route parameters.
-    fn new(route_name: String, route_keys: &Mapping) -> Route {
+    /// Creates and returns the `Route` instance given the route parameters, prepending the
+    /// enclosing `group`'s prefix to the path and falling back to the group's
+    /// middleware/methods/language when the route omits them.
+    fn new(route_name: String, route_keys: &Mapping, group: Option<&Group>) -> Route {
+        let path = Route::parse_path(route_keys, group);
+        let path_split = Route::get_path_split(route_keys, &path);
         Route {
             name: route_name,
-            path: Route::parse_path(route_keys),
-            path_split: Route::get_path_split(route_keys),
-            methods: Route::parse_methods(route_keys),
+            regex: Route::build_regex(&path_split),
+            path_split,
+            path,
+            methods: Route::parse_methods(route_keys, group),
             controller: Route::parse_controller(route_keys),
-            middleware: Route::parse_middleware(route_keys),
+            middleware: Route::parse_middleware(route_keys, group),
             parameters: HashMap::new(),
-            language: Route::parse_language(route_keys),
+            language: Route::parse_language(route_keys, group),
+            synthesized_method: None,
         }
     }
     /// Returns the name of the route.
@@ -79,7 +181,7 @@ impl Route {
     /// ```
     /// # use kalgan_router::Router;
     /// # let router = Router::new("tests/routes.yaml");
-    /// # let route = router.get_route("/", "get").unwrap();
+    /// # let route = router.get_route("/", "get").found().unwrap();
     /// assert_eq!(route.get_name(), &"home".to_string());
     /// ```
     pub fn get_name(&self) -> &String {
@@ -91,9 +193,28 @@ impl Route {
     /// ```
     /// # use kalgan_router::Router;
     /// # let router = Router::new("tests/routes.yaml");
-    /// # let route = router.get_route("/", "get").unwrap();
+    /// # let route = router.get_route("/", "get").found().unwrap();
     /// assert_eq!(route.get_path(), &"/".to_string());
     /// ```
+    ///
+    /// A placeholder name doesn't need to be a valid regex capture-group identifier, and the
+    /// same name can appear more than once in a path:
+    /// ```
+    /// use kalgan_router::Router;
+    /// let router = Router::new("tests/routes-hyphen.yaml");
+    /// let route = router.get_route("/item/42/42", "get").found().unwrap();
+    /// assert_eq!(route.parameters.get("user-id"), Some(&"42".to_string()));
+    /// ```
+    ///
+    /// A requirement that itself contains a capturing group (e.g. a locale alternation) doesn't
+    /// throw off the capture of placeholders that come after it:
+    /// ```
+    /// use kalgan_router::Router;
+    /// let router = Router::new("tests/routes-locale.yaml");
+    /// let route = router.get_route("/en/user/101", "get").found().unwrap();
+    /// assert_eq!(route.parameters.get("lang"), Some(&"en".to_string()));
+    /// assert_eq!(route.parameters.get("id"), Some(&"101".to_string()));
+    /// ```
     pub fn get_path(&self) -> &String {
         &self.path
     }
@@ -103,7 +224,7 @@ impl Route {
     /// ```
     /// # use kalgan_router::Router;
     /// # let router = Router::new("tests/routes.yaml");
-    /// # let route = router.get_route("/", "get").unwrap();
+    /// # let route = router.get_route("/", "get").found().unwrap();
     /// assert_eq!(route.get_methods(), &vec!["get".to_string()]);
     /// ```
     pub fn get_methods(&self) -> &Vec<String> {
@@ -115,7 +236,7 @@ impl Route {
     /// ```
     /// # use kalgan_router::Router;
     /// # let router = Router::new("tests/routes.yaml");
-    /// # let route = router.get_route("/", "get").unwrap();
+    /// # let route = router.get_route("/", "get").found().unwrap();
     /// assert_eq!(route.get_controller(), &"home_controller::index".to_string());
     /// ```
     pub fn get_controller(&self) -> &String {
@@ -127,14 +248,13 @@ impl Route {
     /// ```
     /// # use kalgan_router::Router;
     /// # let router = Router::new("tests/routes.yaml");
-    /// # let route = router.get_route("/", "get").unwrap();
+    /// # let route = router.get_route("/", "get").found().unwrap();
     /// assert_eq!(route.get_middleware(), &"".to_string());
     /// ```
     pub fn get_middleware(&self) -> &String {
         &self.middleware
     }
-    fn get_path_split(route_keys: &Mapping) -> Vec<Parameter> {
-        let uri = Route::parse_path(route_keys);
+    fn get_path_split(route_keys: &Mapping, uri: &str) -> Vec<Parameter> {
         let mut start_path = 0;
         let mut collection = Vec::new();
         if get_regex_for_parameters().find_iter(&uri).count() == 0 {
@@ -187,75 +307,78 @@ impl Route {
         }
         collection
     }
-    fn uri_matches_path(&mut self, uri: &str) -> bool {
-        let mut start = 0;
-        let mut end;
-        let collection = &self.path_split.clone();
-        for (index, parameter) in collection.iter().enumerate() {
-            if parameter.requirement.is_none() {
-                end = start + parameter.value.len();
-                if uri.len() < end || uri[start..end] != parameter.value {
-                    return false;
-                }
-            } else {
-                let mut partial_uri = &uri[start..];
-                if self.path_split.len() >= (index + 2)
-                    && self.path_split[index + 1].requirement.is_none()
-                {
-                    match partial_uri.find(&self.path_split[index + 1].value) {
-                        Some(position) => partial_uri = &partial_uri[..position],
-                        None => return false,
-                    }
-                }
-                match parameter.requirement.as_ref().unwrap().find(&partial_uri) {
-                    Some(value) => {
-                        if value.start() == 0 {
-                            let result = &partial_uri[value.start()..value.end()];
-                            end = start + result.len();
-                            if !parameter.requirement.is_none() {
-                                self.parameters
-                                    .insert(parameter.value.clone(), result.to_string());
-                            }
-                        } else {
-                            return false;
-                        }
+    /// Builds the anchored `Regex` that matches this route's full path in a single pass,
+    /// turning every `{param}` placeholder into a positional capture group bound to its
+    /// requirement. Groups are left unnamed so a placeholder name is never parsed as a regex
+    /// capture-group identifier: `{user-id}` (not a valid group name) and `{id}/{id}` (a
+    /// duplicate name) both compile fine, since `capture_parameters` maps captures back to
+    /// placeholders by position instead.
+    fn build_regex(path_split: &[Parameter]) -> regex::Regex {
+        let mut pattern = String::from("^");
+        for parameter in path_split {
+            match &parameter.requirement {
+                None => pattern.push_str(&regex::escape(&parameter.value)),
+                Some(requirement) => pattern.push_str(&format!(
+                    "({})",
+                    // A requirement like `^[0-9]+` is written to anchor against a standalone
+                    // value; strip those anchors here since the group is embedded inside the
+                    // route's own anchored full-path pattern.
+                    requirement
+                        .as_str()
+                        .trim_start_matches('^')
+                        .trim_end_matches('$')
+                )),
+            }
+        }
+        pattern.push('$');
+        regex::Regex::new(&pattern).unwrap()
+    }
+    /// Extracts the captures produced by matching `uri` against this route's `regex` and stores
+    /// them in `parameters`, pairing each capture group with its placeholder by position (the
+    /// groups appear in `path_split` order since `build_regex` emits them left to right). A
+    /// placeholder's own wrapping group is always the first one it contributes, but its
+    /// requirement may itself contain capturing groups (e.g. `"^(en|es|fr)$"`), so the position
+    /// is advanced by the requirement's full `captures_len()` rather than by one.
+    fn capture_parameters(&mut self, uri: &str) {
+        if let Some(captures) = self.regex.captures(uri) {
+            let mut group = 1;
+            for parameter in &self.path_split {
+                if let Some(requirement) = &parameter.requirement {
+                    if let Some(value) = captures.get(group) {
+                        self.parameters
+                            .insert(parameter.value.clone(), value.as_str().to_string());
                     }
-                    None => return false,
+                    group += requirement.captures_len();
                 }
             }
-            start = end;
-        }
-        if uri.len() == start {
-            debug!("Route \"{}\" matches \"{}\".", self.name, uri);
-            true
-        } else {
-            false
         }
     }
-    fn parse_methods(route_keys: &Mapping) -> Vec<String> {
-        if route_keys.contains_key(&Value::String("methods".to_string())) {
-            let mut collection: Vec<String> = Vec::new();
-            let col: Vec<&str> = kalgan_string::strip(
-                &route_keys[&Value::String("methods".to_string())]
-                    .as_str()
-                    .unwrap(),
-                ',',
-            )
-            .split(",")
-            .collect();
-            for method in col {
-                collection.push(method.trim().to_string().to_lowercase());
+    /// Parses the `methods` key (or, when the route omits it, the enclosing group's) into a
+    /// lowercase list, or an empty `Vec` — meaning the route matches any method — when neither
+    /// sets it or it is set to the `any`/`*` wildcard token.
+    fn parse_methods(route_keys: &Mapping, group: Option<&Group>) -> Vec<String> {
+        match Route::get_key(route_keys, "methods")
+            .or_else(|| group.and_then(|group| group.methods.clone()))
+        {
+            Some(methods) if methods.trim().eq_ignore_ascii_case("any") || methods.trim() == "*" => {
+                Vec::new()
             }
-            collection
-        } else {
-            Vec::new()
+            Some(methods) => kalgan_string::strip(&methods, ',')
+                .split(",")
+                .map(|method| method.trim().to_string().to_lowercase())
+                .collect(),
+            None => Vec::new(),
         }
     }
-    fn parse_path(route_keys: &Mapping) -> String {
-        route_keys[&Value::String("path".to_string())]
+    fn parse_path(route_keys: &Mapping, group: Option<&Group>) -> String {
+        let path = route_keys[&Value::String("path".to_string())]
             .as_str()
             .unwrap()
-            .to_string()
+            .to_string();
+        match group {
+            Some(group) => join_path(&group.prefix, &path),
+            None => path,
+        }
     }
     fn parse_controller(route_keys: &Mapping) -> String {
         route_keys[&Value::String("controller".to_string())]
@@ -264,25 +387,21 @@ impl Route {
             .replace("/", "::")
             .to_string()
     }
-    fn parse_middleware(route_keys: &Mapping) -> String {
-        if route_keys.contains_key(&Value::String("middleware".to_string())) {
-            route_keys[&Value::String("middleware".to_string())]
-                .as_str()
-                .unwrap()
-                .to_string()
-        } else {
-            "".to_string()
-        }
+    fn parse_middleware(route_keys: &Mapping, group: Option<&Group>) -> String {
+        Route::get_key(route_keys, "middleware")
+            .or_else(|| group.and_then(|group| group.middleware.clone()))
+            .unwrap_or_default()
     }
-    fn parse_language(route_keys: &Mapping) -> String {
-        if route_keys.contains_key(&Value::String("language".to_string())) {
-            route_keys[&Value::String("language".to_string())]
-                .as_str()
-                .unwrap()
-                .to_string()
-        } else {
-            "".to_string()
-        }
+    fn parse_language(route_keys: &Mapping, group: Option<&Group>) -> String {
+        Route::get_key(route_keys, "language")
+            .or_else(|| group.and_then(|group| group.language.clone()))
+            .unwrap_or_default()
+    }
+    fn get_key(route_keys: &Mapping, key: &str) -> Option<String> {
+        route_keys
+            .get(&Value::String(key.to_string()))
+            .and_then(|value| value.as_str())
+            .map(|value| value.to_string())
     }
     fn set_language(&mut self) {
         if !self.language.is_empty() {
@@ -299,41 +418,158 @@ impl Router {
     /// use kalgan_router::Router;
     /// let router = Router::new("tests/routes.yaml");
     /// ```
+    ///
+    /// A `group` node composes its `prefix` with every route (and nested group) underneath it,
+    /// and a route that doesn't declare its own `middleware`/`methods`/`language` inherits the
+    /// enclosing group's:
+    /// ```
+    /// use kalgan_router::Router;
+    /// let router = Router::new("tests/routes-group.yaml");
+    /// let route = router.get_route("/api/users", "get").found().unwrap();
+    /// assert_eq!(route.get_middleware(), &"api_middleware::test".to_string());
+    /// let route = router.get_route("/api/v2/users", "get").found().unwrap();
+    /// assert_eq!(route.get_middleware(), &"api_middleware::test".to_string());
+    /// ```
     pub fn new(source: &str) -> Router {
         routes::generate(source)
     }
-    /// Returns the `Route` instance for the given uri and method.
+    /// Creates and returns the `Router` instance given the routes source path, and spawns a
+    /// background watcher that rebuilds the routes collection whenever a `.yaml` file under
+    /// `source` changes, atomically swapping it in so in-flight calls to `get_route`/`get_uri`
+    /// keep seeing a consistent snapshot. If a reload fails to parse, a warning is logged and
+    /// the previous collection keeps serving requests.
     /// # Examples
     /// ```
+    /// use kalgan_router::Router;
+    /// use std::{fs, thread, time::Duration};
+    ///
+    /// let source = std::env::temp_dir().join("kalgan_router_new_watched_doctest.yaml");
+    /// fs::write(&source, "routes:\n  - home:\n      path: /\n      controller: home_controller::index\n      methods: get\n").unwrap();
+    /// let source = source.to_str().unwrap();
+    /// let router = Router::new_watched(source);
+    /// assert_eq!(router.collection().len(), 1);
+    ///
+    /// fs::write(source, "routes:\n  - home:\n      path: /\n      controller: home_controller::index\n      methods: get\n  - about:\n      path: /about\n      controller: about_controller::index\n      methods: get\n").unwrap();
+    /// thread::sleep(Duration::from_secs(2));
+    /// assert_eq!(router.collection().len(), 2);
+    /// ```
+    pub fn new_watched(source: &str) -> Router {
+        let router = routes::generate(source);
+        routes::watch(source, &router.state);
+        router
+    }
+    /// Returns a snapshot of the routes collection.
+    pub fn collection(&self) -> Vec<Route> {
+        self.state.load().collection.clone()
+    }
+    /// Enables synthesizing a `HEAD` match from a route that lists `get`, and an `OPTIONS`
+    /// match from any route whose path matches, regardless of its declared methods. The
+    /// synthesized match is reported via `Route::synthesized_method` so the caller can, for
+    /// instance, suppress the response body for `HEAD`.
+    /// # Examples
+    /// ```
+    /// use kalgan_router::Router;
+    /// let router = Router::new("tests/routes.yaml").with_synthesized_head_and_options();
+    /// let route = router.get_route("/", "head").found().unwrap();
+    /// assert_eq!(route.synthesized_method, Some("head".to_string()));
+    /// let route = router.get_route("/user/101", "options").found().unwrap();
+    /// assert_eq!(route.synthesized_method, Some("options".to_string()));
+    /// ```
+    pub fn with_synthesized_head_and_options(mut self) -> Router {
+        self.synthesize_head_and_options = true;
+        self
+    }
+    /// Matches the given uri and method against the routes collection, distinguishing a route
+    /// whose path matched but whose method didn't from a uri that matched no route at all.
+    /// # Examples
+    /// ```
+    /// # use kalgan_router::Router;
+    /// # let router = Router::new("tests/routes.yaml");
+    /// let route = router.get_route("/", "get").found().unwrap();
+    /// ```
+    ///
+    /// A uri whose path matches but whose method doesn't reports the methods that would have
+    /// matched, instead of being reported as not found:
+    /// ```
     /// # use kalgan_router::Router;
+    /// # use kalgan_router::RouteMatch;
     /// # let router = Router::new("tests/routes.yaml");
-    /// let route = router.get_route("/", "get").unwrap();
+    /// match router.get_route("/", "post") {
+    ///     RouteMatch::MethodNotAllowed { allowed } => assert_eq!(allowed, vec!["get".to_string()]),
+    ///     _ => panic!("expected MethodNotAllowed"),
+    /// }
+    /// ```
+    ///
+    /// A route declared with `methods: any` (or `methods: "*"`) matches every method, and the
+    /// wildcard token is recognized regardless of case:
     /// ```
-    pub fn get_route(&self, uri: &str, method: &str) -> Result<Route, String> {
+    /// # use kalgan_router::Router;
+    /// # let router = Router::new("tests/routes-wildcard.yaml");
+    /// let route = router.get_route("/webhook", "delete").found().unwrap();
+    /// assert!(route.get_methods().is_empty());
+    /// let route = router.get_route("/callback", "delete").found().unwrap();
+    /// assert!(route.get_methods().is_empty());
+    /// ```
+    pub fn get_route(&self, uri: &str, method: &str) -> RouteMatch {
         debug!("Finding a Route for \"{}\"...", uri);
-        for item in &self.collection {
-            let mut route = item.clone();
-            debug!("Checking Route \"{}\"...", route.name);
-            if (route.methods.is_empty()
-                || route.methods.contains(&method.to_string().to_lowercase()))
-                && route.uri_matches_path(&uri)
+        let method = method.to_string().to_lowercase();
+        let mut allowed: Vec<String> = Vec::new();
+        let state = self.state.load();
+        for index in state.regex_set.matches(uri).into_iter() {
+            let item = &state.collection[index];
+            debug!("Checking Route \"{}\"...", item.name);
+            let matches_method = item.methods.is_empty() || item.methods.contains(&method);
+            let synthesized_method = if matches_method {
+                None
+            } else if self.synthesize_head_and_options
+                && method == "head"
+                && item.methods.iter().any(|m| m == "get")
             {
+                Some("head".to_string())
+            } else if self.synthesize_head_and_options && method == "options" {
+                Some("options".to_string())
+            } else {
+                None
+            };
+            if matches_method || synthesized_method.is_some() {
+                let mut route = item.clone();
+                route.capture_parameters(uri);
                 route.set_language();
-                return Ok(route);
+                route.synthesized_method = synthesized_method;
+                debug!("Route \"{}\" matches \"{}\".", route.name, uri);
+                return RouteMatch::Found(route);
             }
+            for route_method in &item.methods {
+                if !allowed.contains(route_method) {
+                    allowed.push(route_method.clone());
+                }
+            }
+        }
+        if self.synthesize_head_and_options && !allowed.is_empty() {
+            // These would have been synthesized for the same path, so report them too.
+            if allowed.iter().any(|m| m == "get") && !allowed.iter().any(|m| m == "head") {
+                allowed.push("head".to_string());
+            }
+            if !allowed.iter().any(|m| m == "options") {
+                allowed.push("options".to_string());
+            }
+        }
+        if allowed.is_empty() {
+            debug!("No route matches \"{}\".", uri);
+            RouteMatch::NotFound
+        } else {
+            debug!("Route \"{}\" matches no method \"{}\".", uri, method);
+            RouteMatch::MethodNotAllowed { allowed }
         }
-        Err(format!(
-            "No route found for uri '{}' and method '{}'",
-            uri, method
-        ))
     }
-    /// Returns the `uri` for the given route name.
+    /// Returns the `uri` for the given route name, rejecting parameters that are missing or
+    /// that don't satisfy the route's requirements, and percent-encoding the supplied values.
     /// # Examples
     /// ```
     /// # use std::collections::HashMap;
     /// # use kalgan_router::Router;
     /// # let router = Router::new("tests/routes.yaml");
-    /// assert_eq!(router.get_uri("home", HashMap::new()), "/".to_string())
+    /// assert_eq!(router.get_uri("home", HashMap::new()).unwrap(), "/".to_string())
     /// ```
     /// ```
     /// # use std::collections::HashMap;
@@ -341,20 +577,82 @@ impl Router {
     /// # let router = Router::new("tests/routes.yaml");
     /// let mut parameters = HashMap::new();
     /// parameters.insert("id", "101".to_string());
-    /// assert_eq!(router.get_uri("user", parameters), "/user/101".to_string())
+    /// assert_eq!(router.get_uri("user", parameters).unwrap(), "/user/101".to_string())
+    /// ```
+    ///
+    /// A value that only partially satisfies the requirement is rejected rather than silently
+    /// accepted:
+    /// ```
+    /// # use std::collections::HashMap;
+    /// # use kalgan_router::Router;
+    /// # use kalgan_router::UrlGenerationError;
+    /// # let router = Router::new("tests/routes.yaml");
+    /// let mut parameters = HashMap::new();
+    /// parameters.insert("id", "101garbage".to_string());
+    /// assert!(matches!(
+    ///     router.get_uri("user", parameters).unwrap_err(),
+    ///     UrlGenerationError::RequirementMismatch { .. }
+    /// ));
+    /// ```
+    ///
+    /// A value is percent-encoded before being substituted, even when the requirement itself
+    /// permits characters like spaces or slashes that would otherwise produce an invalid uri:
+    /// ```
+    /// # use std::collections::HashMap;
+    /// # use kalgan_router::Router;
+    /// # let router = Router::new("tests/routes-freeform.yaml");
+    /// let mut parameters = HashMap::new();
+    /// parameters.insert("query", "rust/web frameworks".to_string());
+    /// assert_eq!(
+    ///     router.get_uri("search", parameters).unwrap(),
+    ///     "/search/rust%2Fweb%20frameworks".to_string()
+    /// );
     /// ```
-    pub fn get_uri(&self, route_name: &str, parameters: HashMap<&str, String>) -> String {
-        for route in &self.collection {
+    pub fn get_uri(
+        &self,
+        route_name: &str,
+        parameters: HashMap<&str, String>,
+    ) -> Result<String, UrlGenerationError> {
+        let state = self.state.load();
+        for route in &state.collection {
             if route.name == route_name {
-                let mut uri = route.path.clone();
-                for (key, value) in parameters {
-                    uri = uri.replace(&format!("{{{}}}", key), &value);
+                let mut uri = String::new();
+                for parameter in &route.path_split {
+                    match &parameter.requirement {
+                        None => uri.push_str(&parameter.value),
+                        Some(requirement) => {
+                            let value = parameters.get(parameter.value.as_str()).ok_or_else(
+                                || UrlGenerationError::MissingParameter {
+                                    route: route_name.to_string(),
+                                    name: parameter.value.clone(),
+                                },
+                            )?;
+                            // Requirement patterns are conventionally written start-anchored
+                            // only (e.g. "^[0-9]+"); `is_match` would accept "101garbage", so
+                            // require the match to span the whole value instead.
+                            let fully_matches = requirement
+                                .find(value)
+                                .is_some_and(|m| m.start() == 0 && m.end() == value.len());
+                            if !fully_matches {
+                                return Err(UrlGenerationError::RequirementMismatch {
+                                    route: route_name.to_string(),
+                                    name: parameter.value.clone(),
+                                    value: value.clone(),
+                                    pattern: requirement.as_str().to_string(),
+                                });
+                            }
+                            uri.push_str(&percent_encoding::utf8_percent_encode(
+                                value,
+                                PATH_SEGMENT,
+                            ).to_string());
+                        }
+                    }
                 }
-                return uri;
+                return Ok(uri);
             }
         }
         warn!("Route \"{}\" not found.", route_name);
-        format!("Route \"{}\" not found.", route_name)
+        Err(UrlGenerationError::RouteNotFound(route_name.to_string()))
     }
 }
 fn get_regex_for_parameters() -> regex::Regex {
@@ -363,3 +661,6 @@ fn get_regex_for_parameters() -> regex::Regex {
 fn get_regex_default_requirement() -> regex::Regex {
     regex::Regex::new(r"[^/]+").unwrap()
 }
+fn default_regex() -> regex::Regex {
+    regex::Regex::new("").unwrap()
+}
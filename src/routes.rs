@@ -1,39 +1,110 @@
+use arc_swap::ArcSwap;
 use log::{debug, error, info, warn};
 use serde::Deserialize;
 use serde_yaml::Value;
 use std::ffi::OsStr;
-use std::{fs, path::Path, vec::Vec};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use std::{fs, path::Path, thread, vec::Vec};
 
 pub fn generate(source: &str) -> crate::Router {
     info!("");
     info!("***********************************************************************************");
     info!("Crate: Router");
     info!("Start Route Generation");
-    let mut router = crate::Router {
-        collection: Vec::new(),
-    };
+    let (collection, _) = build_collection(source);
+    info!("End Route Generation");
+    info!("***********************************************************************************");
+    crate::Router {
+        state: Arc::new(ArcSwap::from_pointee(collection)),
+        synthesize_head_and_options: false,
+    }
+}
+/// Spawns a background thread that polls the mtime of every `.yaml` file under `source` and,
+/// when one is newer than the last seen reload, rebuilds the collection and atomically swaps
+/// it into `state`. A parse error, or even a panic, during reload is logged and the previous
+/// collection is kept, so a single bad edit to the watched source can't kill the watcher thread.
+pub fn watch(source: &str, state: &Arc<ArcSwap<crate::RouteCollection>>) {
+    let source = source.to_string();
+    let state = Arc::clone(state);
+    let mut last_modified = latest_modified(Path::new(&source));
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(1));
+        let modified = latest_modified(Path::new(&source));
+        if modified > last_modified {
+            debug!("Detected a change in route source \"{}\", reloading...", source);
+            match panic::catch_unwind(AssertUnwindSafe(|| build_collection(&source))) {
+                Ok((collection, true)) => {
+                    state.store(Arc::new(collection));
+                    last_modified = modified;
+                }
+                Ok((_, false)) => {
+                    warn!(
+                        "Reload of route source \"{}\" failed, keeping the previous routes.",
+                        source
+                    );
+                }
+                Err(_) => {
+                    warn!(
+                        "Reload of route source \"{}\" panicked, keeping the previous routes.",
+                        source
+                    );
+                }
+            }
+        }
+    });
+}
+fn build_collection(source: &str) -> (crate::RouteCollection, bool) {
+    let mut collection: Vec<crate::Route> = Vec::new();
+    let mut ok = true;
     if Path::new(source).exists() {
         if Path::new(&source).is_dir() {
-            walk_folder_files(Path::new(&source), &mut router.collection);
+            walk_folder_files(Path::new(&source), &mut collection, &mut ok);
         } else {
-            get_file_content(Path::new(&source), &mut router.collection);
+            get_file_content(Path::new(&source), &mut collection, &mut ok);
         }
     } else {
         error!("Source path {} not found.", source);
+        ok = false;
     }
-    match router.collection.len() {
+    match collection.len() {
         0 => info!("Result: No routes have been parsed"),
         1 => info!("Result: 1 route has been parsed"),
-        _ => info!(
-            "Result: {} routes have been parsed",
-            router.collection.len()
-        ),
+        _ => info!("Result: {} routes have been parsed", collection.len()),
+    }
+    let regex_set = regex::RegexSet::new(collection.iter().map(|route| route.regex.as_str()))
+        .expect("Route paths must compile into a valid RegexSet.");
+    (
+        crate::RouteCollection {
+            collection,
+            regex_set,
+        },
+        ok,
+    )
+}
+fn latest_modified(path: &Path) -> Option<SystemTime> {
+    if path.is_dir() {
+        let mut latest = None;
+        if let Ok(read_dir) = fs::read_dir(path) {
+            for entry in read_dir.flatten() {
+                if let Some(modified) = latest_modified(&entry.path()) {
+                    if latest.is_none_or(|current| modified > current) {
+                        latest = Some(modified);
+                    }
+                }
+            }
+        }
+        latest
+    } else if path.extension().and_then(OsStr::to_str).map(str::to_lowercase)
+        == Some("yaml".to_string())
+    {
+        fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    } else {
+        None
     }
-    info!("End Route Generation");
-    info!("***********************************************************************************");
-    router
 }
-fn walk_folder_files(dir: &Path, routes: &mut Vec<crate::Route>) {
+fn walk_folder_files(dir: &Path, routes: &mut Vec<crate::Route>, ok: &mut bool) {
     debug!("Reading folder {}...", dir.display());
     match fs::read_dir(dir) {
         Ok(read_dir) => {
@@ -41,13 +112,13 @@ fn walk_folder_files(dir: &Path, routes: &mut Vec<crate::Route>) {
                 let path = entry.unwrap().path();
                 if path.is_dir() {
                     debug!("{} is dir", path.display());
-                    walk_folder_files(&path, routes);
+                    walk_folder_files(&path, routes, ok);
                 } else {
                     match path.extension().and_then(OsStr::to_str) {
                         Some(extension) => {
                             if extension.to_lowercase() == "yaml" {
                                 debug!("{} is file", path.display());
-                                get_file_content(&path, routes);
+                                get_file_content(&path, routes, ok);
                             } else {
                                 debug!("{} is skipped.", path.display());
                             }
@@ -57,27 +128,67 @@ fn walk_folder_files(dir: &Path, routes: &mut Vec<crate::Route>) {
                 }
             }
         }
-        Err(e) => warn!("{}", e),
+        Err(e) => {
+            warn!("{}", e);
+            *ok = false;
+        }
     }
 }
-fn get_file_content(path: &Path, routes: &mut Vec<crate::Route>) {
+fn get_file_content(path: &Path, routes: &mut Vec<crate::Route>, ok: &mut bool) {
     debug!("Reading file {}...", path.display());
     match fs::read_to_string(path) {
         Ok(config_file) => {
             let document = serde_yaml::Deserializer::from_str(&config_file);
             match Value::deserialize(document) {
                 Ok(parsed_file_content) => {
-                    for route in parsed_file_content["routes"].as_sequence().unwrap() {
-                        let route = route.as_mapping().unwrap().iter().next().unwrap();
-                        routes.push(crate::Route::new(
-                            route.0.as_str().unwrap().to_string(),
-                            route.1.as_mapping().unwrap(),
-                        ));
-                    }
+                    process_routes(
+                        parsed_file_content["routes"].as_sequence().unwrap(),
+                        routes,
+                        None,
+                    );
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    *ok = false;
                 }
-                Err(e) => warn!("{}", e),
             }
         }
-        Err(e) => warn!("{}", e),
+        Err(e) => {
+            warn!("{}", e);
+            *ok = false;
+        }
+    }
+}
+/// Walks a `routes:` sequence, pushing each named route and recursing into each `group` node
+/// with a `Group` whose settings compose with the enclosing one so prefixes nest correctly.
+fn process_routes(sequence: &[Value], routes: &mut Vec<crate::Route>, group: Option<&crate::Group>) {
+    for route in sequence {
+        let (key, value) = route.as_mapping().unwrap().iter().next().unwrap();
+        let key = key.as_str().unwrap();
+        let value = value.as_mapping().unwrap();
+        if key == "group" {
+            let prefix = crate::Route::get_key(value, "prefix").unwrap_or_default();
+            let nested_group = crate::Group {
+                prefix: match group {
+                    Some(group) => crate::join_path(&group.prefix, &prefix),
+                    None => prefix,
+                },
+                middleware: crate::Route::get_key(value, "middleware")
+                    .or_else(|| group.and_then(|group| group.middleware.clone())),
+                methods: crate::Route::get_key(value, "methods")
+                    .or_else(|| group.and_then(|group| group.methods.clone())),
+                language: crate::Route::get_key(value, "language")
+                    .or_else(|| group.and_then(|group| group.language.clone())),
+            };
+            match value
+                .get(&Value::String("routes".to_string()))
+                .and_then(|value| value.as_sequence())
+            {
+                Some(nested_routes) => process_routes(nested_routes, routes, Some(&nested_group)),
+                None => warn!("Group has no nested \"routes\" list."),
+            }
+        } else {
+            routes.push(crate::Route::new(key.to_string(), value, group));
+        }
     }
 }